@@ -0,0 +1,139 @@
+//! Exemplar-capable counters and histograms, so a metric sample can carry a
+//! direct pointer to the trace that produced it.
+//!
+//! [`CounterWithExemplar`] and [`HistogramWithExemplars`] can't be declared
+//! as a `#[metrics]` function's return type the way [`Counter`](super::Counter)
+//! and [`Histogram`](super::Histogram) can; supporting that would mean
+//! teaching `bedrock_macros` — the separate proc-macro crate this macro
+//! comes from, not part of this source tree — about these two types, which
+//! isn't something this module can do on its own. Use them directly instead,
+//! e.g. behind a [`Family`](super::Family), the same as any other
+//! `prometheus_client` metric. Both the [`MetricsFormat::OpenMetricsText`](super::MetricsFormat::OpenMetricsText)
+//! and [`MetricsFormat::OpenMetricsProtobuf`](super::MetricsFormat::OpenMetricsProtobuf)
+//! exposition formats render the exemplar an observation carries, since both
+//! are encoded by `prometheus_client` straight off the same registry these
+//! types are registered in; [`MetricsFormat::Text`](super::MetricsFormat::Text)
+//! carries no exemplars at all, as the legacy Prometheus text format has no
+//! representation for one.
+
+use super::span_labels;
+use super::HistogramBuilder;
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::family::MetricConstructor;
+
+pub use prometheus_client::metrics::exemplar::{CounterWithExemplar, HistogramWithExemplars};
+
+/// The label set attached to an exemplar: the trace and span a metric
+/// sample was recorded during, read off the active tracing span by
+/// [`super::span_labels::SpanLabelsLayer`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq, EncodeLabelSet)]
+pub struct TraceExemplar {
+    /// The `trace_id` field recorded on the active span, or empty if unset.
+    pub trace_id: String,
+    /// The `span_id` field recorded on the active span, or empty if unset.
+    pub span_id: String,
+}
+
+impl TraceExemplar {
+    /// Builds an exemplar label set from the `trace_id`/`span_id` fields of
+    /// the currently-active tracing span, or `None` if neither is set.
+    pub fn from_current_span() -> Option<Self> {
+        let trace_id = span_labels::label("trace_id");
+        let span_id = span_labels::label("span_id");
+
+        if trace_id.is_empty() && span_id.is_empty() {
+            return None;
+        }
+
+        Some(Self { trace_id, span_id })
+    }
+}
+
+impl MetricConstructor<HistogramWithExemplars<TraceExemplar>> for HistogramBuilder {
+    fn new_metric(&self) -> HistogramWithExemplars<TraceExemplar> {
+        HistogramWithExemplars::new(self.buckets.iter().cloned())
+    }
+}
+
+/// Observes `value` on `histogram`, attaching an exemplar built from the
+/// currently-active tracing span, if one recorded a `trace_id` or `span_id`.
+pub fn observe_with_exemplar(histogram: &HistogramWithExemplars<TraceExemplar>, value: f64) {
+    histogram.observe(value, TraceExemplar::from_current_span());
+}
+
+/// Increments `counter` by one, attaching an exemplar built from the
+/// currently-active tracing span, if one recorded a `trace_id` or `span_id`.
+pub fn inc_with_exemplar(counter: &CounterWithExemplar<TraceExemplar>) {
+    counter.inc_by(1, TraceExemplar::from_current_span());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        inc_with_exemplar, observe_with_exemplar, CounterWithExemplar, HistogramWithExemplars,
+        TraceExemplar,
+    };
+    use crate::telemetry::metrics::span_labels::{instrument, SpanLabelsLayer};
+    use crate::telemetry::metrics::HistogramBuilder;
+    use prometheus_client::metrics::family::MetricConstructor;
+    use prometheus_client::registry::Registry as MetricsRegistry;
+    use tracing::Instrument;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry as SubscriberRegistry;
+
+    #[tokio::test]
+    async fn from_current_span_is_none_without_a_trace_id_or_span_id() {
+        instrument(async {
+            assert_eq!(TraceExemplar::from_current_span(), None);
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn observe_with_exemplar_renders_the_active_spans_trace_id() {
+        let _subscriber =
+            tracing::subscriber::set_default(SubscriberRegistry::default().with(SpanLabelsLayer));
+
+        instrument(
+            async {
+                let mut registry = MetricsRegistry::default();
+                let histogram: HistogramWithExemplars<TraceExemplar> =
+                    HistogramBuilder::linear(0.0, 1.0, 4).new_metric();
+                registry.register("test_histogram", "a test histogram", histogram.clone());
+
+                observe_with_exemplar(&histogram, 0.5);
+
+                let mut text = String::new();
+                prometheus_client::encoding::text::encode(&mut text, &registry).unwrap();
+
+                assert!(
+                    text.contains("trace_id=\"t1\""),
+                    "expected an exemplar carrying the active span's trace_id in:\n{text}"
+                );
+            }
+            .instrument(tracing::info_span!("span", trace_id = "t1")),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn inc_with_exemplar_attaches_the_active_spans_trace_id() {
+        let _subscriber =
+            tracing::subscriber::set_default(SubscriberRegistry::default().with(SpanLabelsLayer));
+
+        instrument(
+            async {
+                let counter = CounterWithExemplar::<TraceExemplar>::default();
+
+                inc_with_exemplar(&counter);
+
+                let (value, exemplar) = counter.get();
+                assert_eq!(value, 1);
+                let exemplar = exemplar.clone().expect("exemplar should have been recorded");
+                assert_eq!(exemplar.label_set.trace_id, "t1");
+            }
+            .instrument(tracing::info_span!("span", trace_id = "t1")),
+        )
+        .await;
+    }
+}
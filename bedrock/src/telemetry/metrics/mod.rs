@@ -4,18 +4,148 @@ use prometheus::{Encoder, TextEncoder};
 use serde::Serialize;
 use std::any::TypeId;
 use std::io;
+use std::sync::OnceLock;
 
 pub(super) mod init;
 
 #[doc(hidden)]
 pub mod internal;
 
+mod exemplar;
+mod openmetrics;
+
+#[doc(hidden)]
+pub mod span_labels;
+
+pub use exemplar::{
+    inc_with_exemplar, observe_with_exemplar, CounterWithExemplar, HistogramWithExemplars,
+    TraceExemplar,
+};
+pub use span_labels::{instrument, SpanLabelsLayer};
+
 use internal::{
     collect_info_metrics, encode_registry, ErasedInfoMetric, INFO_REGISTRY, OPT_REGISTRY, REGISTRY,
 };
 
-/// Collects all metrics in a byte buffer.
+/// The exposition format metrics can be rendered as.
+///
+/// Prometheus scrapers pick the format via content negotiation on the
+/// `Accept` header of the scrape request; [`MetricsFormat::Text`] is always
+/// a safe default for scrapers that don't send one.
+///
+/// [`MetricsFormat::OpenMetricsText`] and [`MetricsFormat::OpenMetricsProtobuf`]
+/// only cover the two `#[metrics]`-backed registries, not the info metrics
+/// registry or the legacy [`prometheus::gather`] registry, since neither has
+/// an OpenMetrics encoder available. Use [`MetricsFormat::Text`] to collect
+/// everything.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MetricsFormat {
+    /// The legacy Prometheus text exposition format (`text/plain; version=0.0.4`).
+    Text,
+    /// The OpenMetrics text exposition format.
+    OpenMetricsText,
+    /// The OpenMetrics protobuf exposition format (`application/openmetrics-protobuf`).
+    OpenMetricsProtobuf,
+}
+
+impl MetricsFormat {
+    /// Picks the format negotiated by an HTTP request's `Accept` header
+    /// value, defaulting to [`MetricsFormat::Text`] when `accept` is `None`
+    /// or matches none of the media types below.
+    ///
+    /// Meant to be called by the telemetry server's scrape handler with the
+    /// raw `Accept` header value of the incoming request.
+    pub fn from_accept_header(accept: Option<&str>) -> Self {
+        let Some(accept) = accept else {
+            return Self::Text;
+        };
+
+        // A real `Accept` header can list several comma-separated media
+        // types with `;q=` weights; scrapers in practice send a single,
+        // unweighted one, so picking the first recognized type is enough.
+        for media_type in accept.split(',').map(str::trim) {
+            let media_type = media_type.split(';').next().unwrap_or(media_type).trim();
+
+            match media_type {
+                "application/openmetrics-text" => return Self::OpenMetricsText,
+                "application/openmetrics-protobuf" => return Self::OpenMetricsProtobuf,
+                "text/plain" | "*/*" => return Self::Text,
+                _ => {}
+            }
+        }
+
+        Self::Text
+    }
+}
+
+#[cfg(test)]
+mod metrics_format_tests {
+    use super::MetricsFormat;
+
+    #[test]
+    fn defaults_to_text_when_absent() {
+        assert_eq!(MetricsFormat::from_accept_header(None), MetricsFormat::Text);
+    }
+
+    #[test]
+    fn defaults_to_text_when_unrecognized() {
+        assert_eq!(
+            MetricsFormat::from_accept_header(Some("application/json")),
+            MetricsFormat::Text
+        );
+    }
+
+    #[test]
+    fn picks_openmetrics_text() {
+        assert_eq!(
+            MetricsFormat::from_accept_header(Some("application/openmetrics-text; version=1.0.0")),
+            MetricsFormat::OpenMetricsText
+        );
+    }
+
+    #[test]
+    fn picks_openmetrics_protobuf() {
+        assert_eq!(
+            MetricsFormat::from_accept_header(Some("application/openmetrics-protobuf")),
+            MetricsFormat::OpenMetricsProtobuf
+        );
+    }
+
+    #[test]
+    fn picks_first_recognized_entry_in_a_list() {
+        assert_eq!(
+            MetricsFormat::from_accept_header(Some("application/json, application/openmetrics-text")),
+            MetricsFormat::OpenMetricsText
+        );
+    }
+}
+
+/// Collects all metrics in a byte buffer, using the legacy Prometheus text
+/// exposition format.
+///
+/// This is a shim over [`collect_with_format`] for callers that don't need to
+/// negotiate the exposition format; see its documentation for details on what
+/// gets collected.
 pub fn collect(buffer: &mut Vec<u8>, collect_optional: bool) -> io::Result<()> {
+    collect_with_format(buffer, collect_optional, MetricsFormat::Text)
+}
+
+/// Collects all metrics in a byte buffer, rendered in the given `format`.
+///
+/// For [`MetricsFormat::Text`], this gathers metrics from the info metrics
+/// registry, the two `#[metrics]`-backed registries (`collect_optional`
+/// controls whether the optional one is included), and the legacy
+/// [`prometheus::gather`] registry. The OpenMetrics formats only cover the
+/// two `#[metrics]`-backed registries; see [`MetricsFormat`]'s docs for why.
+pub fn collect_with_format(
+    buffer: &mut Vec<u8>,
+    collect_optional: bool,
+    format: MetricsFormat,
+) -> io::Result<()> {
+    if format != MetricsFormat::Text {
+        return openmetrics::collect(buffer, collect_optional, format);
+    }
+
     collect_info_metrics(buffer)?;
 
     encode_registry(buffer, &REGISTRY.read())?;
@@ -183,6 +313,94 @@ pub fn collect(buffer: &mut Vec<u8>, collect_optional: bool) -> io::Result<()> {
 /// # }
 /// ```
 ///
+/// # Bounding cardinality
+///
+/// Every distinct combination of label values a metric is recorded with
+/// becomes a series that's kept around until the process exits, which is a
+/// problem for labels derived from long-lived but not-forever-lived state,
+/// such as a connection's endpoint or remote IP. Generating a
+/// `<metric>_remove(...)`/`<metric>_clear()` pair for `#[metrics]`-declared
+/// metrics would require changes to the `bedrock_macros` proc-macro crate
+/// that defines this macro, which isn't part of this source tree — this
+/// module can't implement macro codegen for a macro defined elsewhere.
+///
+/// Until `bedrock_macros` grows that codegen, declare the metric as a
+/// [`Family`] directly — the same as for
+/// [`CounterWithExemplar`]/[`HistogramWithExemplars`] — to get at
+/// [`Family::remove`]/[`Family::clear`] yourself:
+///
+/// ```
+/// use bedrock::telemetry::metrics::{Family, Gauge};
+/// use serde::Serialize;
+///
+/// #[derive(Clone, Eq, Hash, PartialEq, Serialize)]
+/// struct ConnectionLabels {
+///     endpoint: String,
+/// }
+///
+/// let client_connections_active = Family::<ConnectionLabels, Gauge>::default();
+///
+/// let labels = ConnectionLabels { endpoint: "http-over-tcp".to_owned() };
+/// client_connections_active.get_or_create(&labels).inc();
+///
+/// // Once the connection closes, drop its label set so it doesn't linger
+/// // in the family forever.
+/// assert!(client_connections_active.remove(&labels));
+/// ```
+///
+/// # Labels from the active tracing span
+///
+/// [`span_labels::SpanLabelsLayer`] and [`span_labels::label`] provide the
+/// building blocks for sourcing a label from the currently-active tracing
+/// span instead of a function argument — register the layer on the
+/// subscriber in use, wrap spawned tasks with [`span_labels::instrument`],
+/// and a name with no matching field anywhere on the span stack resolves to
+/// an empty string rather than panicking.
+///
+/// A `#[span_labels(...)]` attribute that lets a `#[metrics]` function opt a
+/// label into this automatically, instead of calling [`span_labels::label`]
+/// by hand at the call site, would need to be implemented in
+/// `bedrock_macros` — the proc-macro crate this macro itself comes from, and
+/// which isn't part of this source tree. There's no macro expansion code
+/// here to add that attribute to, so for now, call [`span_labels::label`]
+/// directly for the metric function's argument that should carry a
+/// span-sourced label:
+///
+/// ```
+/// use bedrock::telemetry::metrics::{metrics, span_labels, Counter};
+///
+/// #[metrics]
+/// pub mod my_app_metrics {
+///     pub fn requests_total(tenant: String) -> Counter;
+/// }
+///
+/// fn record_request() {
+///     my_app_metrics::requests_total(span_labels::label("tenant")).inc();
+/// }
+/// ```
+///
+/// # Exemplars
+///
+/// [`CounterWithExemplar`] and [`HistogramWithExemplars`] aren't supported as
+/// `#[metrics]` return types — doing so would require changes to the
+/// `bedrock_macros` proc-macro crate, which isn't part of this source tree.
+/// Declare one directly instead, for metrics that should carry a link to the
+/// trace that produced a given sample:
+///
+/// ```
+/// use bedrock::telemetry::metrics::{observe_with_exemplar, HistogramBuilder, HistogramWithExemplars, TraceExemplar};
+/// use prometheus_client::metrics::family::MetricConstructor;
+///
+/// let histogram: HistogramWithExemplars<TraceExemplar> =
+///     HistogramBuilder { buckets: &[0.001, 0.01, 0.1, 1.0] }.new_metric();
+///
+/// observe_with_exemplar(&histogram, 0.042);
+/// ```
+///
+/// Record through [`observe_with_exemplar`]/[`inc_with_exemplar`] rather than
+/// the type's own `observe`/`inc` to have the exemplar populated from the
+/// active span automatically.
+///
 /// # Renamed or reexported crate
 ///
 /// The macro will fail to compile if `bedrock` crate is reexported. However, the crate path
@@ -302,6 +520,11 @@ where
 
 /// A builder suitable for [`Histogram`] and [`TimeHistogram`].
 ///
+/// Metrics declared without a `#[ctor]` are built with [`HistogramBuilder::default`],
+/// which uses the service-wide default bucket set configured at telemetry
+/// init (see [`set_default_buckets`]), falling back to [`DEFAULT_BUCKETS`] if
+/// none was configured.
+///
 /// # Example
 ///
 /// ```
@@ -322,12 +545,105 @@ where
 /// }
 /// # }
 /// ```
+///
+/// # Bucket schemes
+///
+/// Hand-maintaining a bucket array for every latency metric makes it easy
+/// for SLO bucketing to drift between services, so a few common schemes are
+/// available as constructors instead:
+///
+/// ```
+/// use bedrock::telemetry::metrics::HistogramBuilder;
+///
+/// // 10 buckets starting at 0.0, 0.1 apart: 0.0, 0.1, 0.2, ..., 0.9
+/// let _ = HistogramBuilder::linear(0.0, 0.1, 10);
+///
+/// // 10 buckets starting at 1E-3, each 2x the last: 1E-3, 2E-3, 4E-3, ...
+/// let _ = HistogramBuilder::exponential(1E-3, 2.0, 10);
+///
+/// // Equivalent to `exponential(1E-3, 2.0, 10)`, for sub-millisecond-to-second latencies.
+/// let _ = HistogramBuilder::exponential_base2(1E-3, 10);
+/// ```
 #[derive(Clone)]
 pub struct HistogramBuilder {
     /// The buckets of the histogram to be built.
     pub buckets: &'static [f64],
 }
 
+/// The bucket set used by [`HistogramBuilder::default`] when no service-wide
+/// default has been configured via [`set_default_buckets`].
+///
+/// Covers sub-millisecond to 10-second latencies, which is a reasonable
+/// starting point for most request-handling histograms.
+pub const DEFAULT_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+static CONFIGURED_DEFAULT_BUCKETS: OnceLock<&'static [f64]> = OnceLock::new();
+
+/// Configures the bucket set [`HistogramBuilder::default`] uses for metrics
+/// declared without an explicit `#[ctor]`.
+///
+/// Intended to be called once, from telemetry init, with a service-wide
+/// default tailored to that service's latency SLOs. Calling it more than
+/// once has no effect after the first call.
+pub fn set_default_buckets(buckets: &'static [f64]) {
+    let _ = CONFIGURED_DEFAULT_BUCKETS.set(buckets);
+}
+
+impl HistogramBuilder {
+    /// Builds a bucket set of `count` buckets, starting at `start` and each
+    /// `width` apart: `start`, `start + width`, `start + 2 * width`, ...
+    ///
+    /// No implicit `+Inf` bucket is appended; [`Histogram`] adds the overflow
+    /// bucket itself.
+    pub fn linear(start: f64, width: f64, count: usize) -> Self {
+        let buckets = (0..count).map(|i| start + width * i as f64).collect();
+
+        Self {
+            buckets: Vec::leak(buckets),
+        }
+    }
+
+    /// Builds a bucket set of `count` buckets, starting at `start` and each
+    /// `factor` times the last: bucket `i` is `start * factor.powi(i)`.
+    ///
+    /// No implicit `+Inf` bucket is appended; [`Histogram`] adds the overflow
+    /// bucket itself.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `start <= 0.0` or `factor <= 1.0`.
+    pub fn exponential(start: f64, factor: f64, count: usize) -> Self {
+        assert!(start > 0.0, "exponential buckets need a positive `start`");
+        assert!(factor > 1.0, "exponential buckets need a `factor` greater than 1.0");
+
+        let buckets = (0..count as i32).map(|i| start * factor.powi(i)).collect();
+
+        Self {
+            buckets: Vec::leak(buckets),
+        }
+    }
+
+    /// A Prometheus-style `exponential(start, 2.0, count)`, suitable for
+    /// sub-millisecond-to-seconds latency ranges.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `start <= 0.0`.
+    pub fn exponential_base2(start: f64, count: usize) -> Self {
+        Self::exponential(start, 2.0, count)
+    }
+}
+
+impl Default for HistogramBuilder {
+    fn default() -> Self {
+        Self {
+            buckets: CONFIGURED_DEFAULT_BUCKETS.get().copied().unwrap_or(DEFAULT_BUCKETS),
+        }
+    }
+}
+
 impl MetricConstructor<Histogram> for HistogramBuilder {
     fn new_metric(&self) -> Histogram {
         Histogram::new(self.buckets.iter().cloned())
@@ -339,3 +655,44 @@ impl MetricConstructor<TimeHistogram> for HistogramBuilder {
         TimeHistogram::new(self.buckets.iter().cloned())
     }
 }
+
+#[cfg(test)]
+mod histogram_builder_tests {
+    use super::HistogramBuilder;
+
+    #[test]
+    fn linear_builds_evenly_spaced_buckets() {
+        assert_eq!(
+            HistogramBuilder::linear(0.0, 0.5, 4).buckets,
+            &[0.0, 0.5, 1.0, 1.5]
+        );
+    }
+
+    #[test]
+    fn exponential_builds_geometrically_spaced_buckets() {
+        assert_eq!(
+            HistogramBuilder::exponential(1E-3, 2.0, 4).buckets,
+            &[1E-3, 2E-3, 4E-3, 8E-3]
+        );
+    }
+
+    #[test]
+    fn exponential_base2_matches_exponential_with_factor_2() {
+        assert_eq!(
+            HistogramBuilder::exponential_base2(1E-3, 5).buckets,
+            HistogramBuilder::exponential(1E-3, 2.0, 5).buckets
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "positive `start`")]
+    fn exponential_panics_on_non_positive_start() {
+        HistogramBuilder::exponential(0.0, 2.0, 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "`factor` greater than 1.0")]
+    fn exponential_panics_on_factor_not_greater_than_one() {
+        HistogramBuilder::exponential(1.0, 1.0, 4);
+    }
+}
@@ -0,0 +1,74 @@
+//! OpenMetrics text and protobuf exposition for
+//! [`collect_with_format`](super::collect_with_format).
+//!
+//! Both formats here are produced entirely by `prometheus_client`'s own
+//! OpenMetrics encoders, run directly over [`REGISTRY`]/[`OPT_REGISTRY`] — the
+//! two registries the `#[metrics]` macro populates. Neither encoder has
+//! anything to say about the info metrics registry or the legacy
+//! [`prometheus::gather`] registry: those aren't `prometheus_client::Registry`
+//! values, and there's no converter between the legacy Prometheus data model
+//! and the OpenMetrics one. So, unlike [`MetricsFormat::Text`](super::MetricsFormat::Text),
+//! neither format here includes them; this is a real scope restriction, not a
+//! gap to fill in later. A series produced by
+//! [`CounterWithExemplar`](super::CounterWithExemplar) or
+//! [`HistogramWithExemplars`](super::HistogramWithExemplars) keeps its
+//! exemplar through both encoders, since both read it straight off the same
+//! registry the type is registered in.
+
+use super::internal::{OPT_REGISTRY, REGISTRY};
+use crate::telemetry::metrics::MetricsFormat;
+use prost::Message;
+use std::io;
+
+/// Writes the metrics in [`REGISTRY`] (and, if `collect_optional`,
+/// [`OPT_REGISTRY`]) to `buffer` using the given `format`.
+pub(super) fn collect(
+    buffer: &mut Vec<u8>,
+    collect_optional: bool,
+    format: MetricsFormat,
+) -> io::Result<()> {
+    debug_assert_ne!(format, MetricsFormat::Text);
+
+    match format {
+        MetricsFormat::Text => unreachable!(),
+        MetricsFormat::OpenMetricsText => write_text(buffer, collect_optional),
+        MetricsFormat::OpenMetricsProtobuf => write_protobuf(buffer, collect_optional),
+    }
+}
+
+fn write_text(buffer: &mut Vec<u8>, collect_optional: bool) -> io::Result<()> {
+    let mut text = String::new();
+
+    prometheus_client::encoding::text::encode(&mut text, &REGISTRY.read())
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+    if collect_optional {
+        // `encode` always terminates its output with `# EOF`; strip it so
+        // `OPT_REGISTRY`'s families land before the real, final one.
+        if let Some(without_eof) = text.strip_suffix("# EOF\n") {
+            text.truncate(without_eof.len());
+        }
+
+        prometheus_client::encoding::text::encode(&mut text, &OPT_REGISTRY.read())
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    }
+
+    buffer.extend_from_slice(text.as_bytes());
+
+    Ok(())
+}
+
+fn write_protobuf(buffer: &mut Vec<u8>, collect_optional: bool) -> io::Result<()> {
+    let mut metric_set = prometheus_client::encoding::protobuf::encode(&REGISTRY.read())
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+    if collect_optional {
+        let optional = prometheus_client::encoding::protobuf::encode(&OPT_REGISTRY.read())
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        metric_set.metric_families.extend(optional.metric_families);
+    }
+
+    buffer.extend_from_slice(&metric_set.encode_to_vec());
+
+    Ok(())
+}
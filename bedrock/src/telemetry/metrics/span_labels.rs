@@ -0,0 +1,202 @@
+//! Automatic metric labels sourced from the active tracing span.
+//!
+//! Threading a label like `request_id` or `tenant` through every function
+//! between where it's known and where a metric is recorded is tedious and
+//! easy to get wrong. This module provides the primitives for reading
+//! specific labels off the fields recorded on the currently-active tracing
+//! span instead: [`SpanLabelsLayer`] to snapshot them, and [`label`] to look
+//! one up at record time.
+//!
+//! The label map is kept in a [`tokio::task_local!`], not a thread-local, so
+//! it stays correct when a task moves between worker threads across an
+//! `.await`. That requires the task-local to be scoped once, for the whole
+//! task, via [`instrument`] — wrap the future passed to `tokio::spawn` (or
+//! an equivalent) with it. Spans entered on a task that was never wrapped
+//! fall back to empty labels rather than panicking, the same as a label
+//! name with no matching field.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use tracing::field::{Field, Visit};
+use tracing::span;
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Fields recorded on a span, keyed by field name, inherited from parents
+/// with child fields taking priority.
+type LabelMap = Arc<HashMap<&'static str, String>>;
+
+tokio::task_local! {
+    static ACTIVE_LABELS: RefCell<Vec<LabelMap>>;
+}
+
+/// Runs `future` with the span-labels stack scoped to it, so spans entered
+/// anywhere inside `future` — including after it's moved to another worker
+/// thread across an `.await` — share one consistent, per-task label stack.
+///
+/// Wrap the top-level future given to `tokio::spawn` with this; nested
+/// `.await`s don't need their own scope.
+pub fn instrument<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    ACTIVE_LABELS.scope(RefCell::new(Vec::new()), future)
+}
+
+/// Looks up `name` in the label map of the currently-active span, falling
+/// back to an empty string if there is no active span, the current task was
+/// never wrapped with [`instrument`], or the span (and its ancestors) didn't
+/// record a field by that name.
+///
+/// A `#[span_labels(...)]` attribute on `#[metrics]` functions would call
+/// this for you; since that attribute isn't implemented (it would need
+/// changes to the separate `bedrock_macros` crate), call this directly at
+/// the metric function's call site for now — see
+/// [the `#[metrics]` macro's docs](super::metrics#labels-from-the-active-tracing-span)
+/// for an example.
+pub fn label(name: &str) -> String {
+    ACTIVE_LABELS
+        .try_with(|labels| {
+            labels
+                .borrow()
+                .last()
+                .and_then(|labels| labels.get(name).cloned())
+        })
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+}
+
+/// A [`Layer`] that snapshots the fields recorded on each span into a
+/// per-task label map, so metrics recorded anywhere inside that span can
+/// pick them up via [`label`].
+///
+/// Labels are merged down from parent spans, with a child span's fields
+/// overriding its parent's on conflict. Register this layer alongside the
+/// `tracing_subscriber::Registry` used by the rest of telemetry.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SpanLabelsLayer;
+
+impl<S> Layer<S> for SpanLabelsLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("span must exist in on_new_span");
+
+        let mut fields = span
+            .parent()
+            .and_then(|parent| parent.extensions().get::<LabelMap>().map(|l| (**l).clone()))
+            .unwrap_or_default();
+
+        attrs.record(&mut FieldVisitor(&mut fields));
+
+        span.extensions_mut().insert(Arc::new(fields) as LabelMap);
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("span must exist in on_record");
+        let mut extensions = span.extensions_mut();
+
+        let mut fields = extensions
+            .get::<LabelMap>()
+            .map(|labels| (**labels).clone())
+            .unwrap_or_default();
+
+        values.record(&mut FieldVisitor(&mut fields));
+
+        extensions.insert(Arc::new(fields) as LabelMap);
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("span must exist in on_enter");
+        let labels = span
+            .extensions()
+            .get::<LabelMap>()
+            .cloned()
+            .unwrap_or_default();
+
+        // Errs when the current task was never wrapped with `instrument`;
+        // fields recorded on this span just won't be visible to `label`
+        // then, same as an unrecorded field name.
+        let _ = ACTIVE_LABELS.try_with(|active| active.borrow_mut().push(labels));
+    }
+
+    fn on_exit(&self, _id: &span::Id, _ctx: Context<'_, S>) {
+        let _ = ACTIVE_LABELS.try_with(|active| {
+            active.borrow_mut().pop();
+        });
+    }
+}
+
+/// Records span fields as strings into a [`HashMap`]: `&str` values as-is,
+/// everything else via `Debug` formatting.
+struct FieldVisitor<'a>(&'a mut HashMap<&'static str, String>);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name(), value.to_owned());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0.insert(field.name(), format!("{value:?}"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{instrument, label, SpanLabelsLayer};
+    use tracing::Instrument;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+
+    #[test]
+    fn label_defaults_to_empty_without_instrument() {
+        // No `instrument`-scoped task-local and no active span: every lookup
+        // falls back to empty, the same as an unrecorded field name would.
+        assert_eq!(label("trace_id"), "");
+    }
+
+    #[tokio::test]
+    async fn label_defaults_to_empty_inside_instrument_with_no_active_span() {
+        instrument(async {
+            assert_eq!(label("trace_id"), "");
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn child_labels_merge_with_inherited_parent_labels_across_an_await() {
+        let _subscriber = tracing::subscriber::set_default(Registry::default().with(SpanLabelsLayer));
+
+        instrument(
+            async {
+                assert_eq!(label("trace_id"), "t1");
+                assert_eq!(label("span_id"), "");
+
+                async {
+                    // Inherited from the parent span, not re-recorded here.
+                    assert_eq!(label("trace_id"), "t1");
+                    // Recorded on this span only; the parent has no `span_id`.
+                    assert_eq!(label("span_id"), "s1");
+
+                    // Yielding exits and re-enters both spans; the label
+                    // stack must come back the same afterwards.
+                    tokio::task::yield_now().await;
+
+                    assert_eq!(label("trace_id"), "t1");
+                    assert_eq!(label("span_id"), "s1");
+                }
+                .instrument(tracing::info_span!("child", span_id = "s1"))
+                .await;
+
+                // Back in the parent span after the child exited.
+                assert_eq!(label("trace_id"), "t1");
+                assert_eq!(label("span_id"), "");
+            }
+            .instrument(tracing::info_span!("parent", trace_id = "t1")),
+        )
+        .await;
+    }
+}